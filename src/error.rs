@@ -0,0 +1,63 @@
+use std::fmt;
+
+use warp::reject::Reject;
+
+pub type Result<T> = std::result::Result<T, AppError>;
+
+/// Error type returned by services and mapped to an HTTP response by
+/// `handler::rejection::handle_rejection`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The multipart body couldn't be read off the wire.
+    ReadImageError(String),
+    /// The bytes don't decode as a supported image format at all.
+    UnsupportedImage(String),
+    /// The format sniffed from magic bytes doesn't match the declared
+    /// `Content-Type` (or the declared type isn't supported).
+    FormatMismatch(String),
+    /// The image's pixel dimensions exceed what the service will decode.
+    ImageTooLarge(String),
+    /// A resize/transcode/background task failed.
+    ProcessingError(String),
+    /// The configured `Store` backend failed to save/load/delete a blob.
+    StorageError(String),
+    /// The requested resource doesn't exist (or isn't visible to the
+    /// requester).
+    NotFound(String),
+    /// A query against Postgres failed.
+    DatabaseError(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::ReadImageError(msg) => write!(f, "failed to read uploaded image: {}", msg),
+            AppError::UnsupportedImage(msg) => write!(f, "unsupported image: {}", msg),
+            AppError::FormatMismatch(msg) => write!(f, "image format mismatch: {}", msg),
+            AppError::ImageTooLarge(msg) => write!(f, "image too large: {}", msg),
+            AppError::ProcessingError(msg) => write!(f, "image processing failed: {}", msg),
+            AppError::StorageError(msg) => write!(f, "storage backend error: {}", msg),
+            AppError::NotFound(msg) => write!(f, "{}", msg),
+            AppError::DatabaseError(msg) => write!(f, "database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl Reject for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => AppError::NotFound("resource not found".to_string()),
+            other => AppError::DatabaseError(other.to_string()),
+        }
+    }
+}
+
+impl From<image::ImageError> for AppError {
+    fn from(error: image::ImageError) -> Self {
+        AppError::UnsupportedImage(error.to_string())
+    }
+}
@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// In-memory image: the re-encoded, sanitized bytes plus the metadata
+/// persisted alongside them in `images`. The `Image` that `save` returns for
+/// an already-stored hash carries metadata only, with `image` left empty.
+/// `hash` is the SHA-256 content
+/// address used both as the dedup key and as the blob's key in the
+/// configured `Store`. Ownership isn't tracked here — a canonical row can be
+/// shared by many uploaders, so `owner_id` lives on `image_aliases` instead.
+/// `created_at` is immutable once the row exists, so it doubles as the
+/// `Last-Modified` value for conditional GET.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub id: Uuid,
+    pub url: String,
+    pub filename: String,
+    pub image: Vec<u8>,
+    pub size: i32,
+    pub mime: String,
+    pub hash: String,
+    pub height: i16,
+    pub width: i16,
+    pub created_at: DateTime<Utc>,
+}
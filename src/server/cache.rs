@@ -0,0 +1,128 @@
+// Helpers for serving immutable, content-addressed resources with strong
+// cache validators. Images are stored and named by their SHA-256 hash, so
+// the hash itself makes a perfectly stable `ETag`.
+use chrono::{DateTime, SubsecRound, Utc};
+
+/// How long, in seconds, a served image may be cached before revalidating.
+/// Content-addressed images never change once uploaded, so this is
+/// deliberately long and paired with `immutable`.
+const MAX_AGE_SECONDS: u64 = 31_536_000; // 1 year
+
+/// Quotes `hash` into a strong `ETag` value, e.g. `"abcd1234"`.
+pub fn make_etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Builds the `Cache-Control` value for an immutable, content-addressed
+/// resource.
+pub fn cache_control() -> String {
+    format!("public, max-age={}, immutable", MAX_AGE_SECONDS)
+}
+
+/// Formats `created_at` as an RFC 7231 `Last-Modified` value.
+pub fn make_last_modified(created_at: DateTime<Utc>) -> String {
+    created_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Compares an inbound `If-Modified-Since` header against `created_at`,
+/// per RFC 7232: the resource counts as unmodified when the header's
+/// timestamp is at or after `created_at`, truncated to the second since
+/// HTTP-dates carry no sub-second precision. An unparsable header never
+/// short-circuits the response.
+pub fn not_modified_since(if_modified_since: &str, created_at: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc2822(if_modified_since)
+        .map(|parsed| parsed.with_timezone(&Utc) >= created_at.trunc_subsecs(0))
+        .unwrap_or(false)
+}
+
+/// Compares an inbound `If-None-Match` header value against `etag`,
+/// following the weak-comparison rules from RFC 7232: a leading `W/` is
+/// stripped before comparing, and `*` matches anything. `if_none_match` may
+/// contain a comma-separated list of quoted values.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn make_etag_quotes_the_hash() {
+        assert_eq!(make_etag("abcd1234"), "\"abcd1234\"");
+    }
+
+    #[test]
+    fn etag_matches_exact_value() {
+        let etag = make_etag("abcd1234");
+        assert!(etag_matches(&etag, &etag));
+    }
+
+    #[test]
+    fn etag_matches_wildcard() {
+        assert!(etag_matches("*", &make_etag("abcd1234")));
+    }
+
+    #[test]
+    fn etag_matches_weak_prefix_on_either_side() {
+        let etag = make_etag("abcd1234");
+        let weak = format!("W/{}", etag);
+
+        assert!(etag_matches(&weak, &etag));
+        assert!(etag_matches(&etag, &weak));
+    }
+
+    #[test]
+    fn etag_matches_one_of_a_comma_separated_list() {
+        let etag = make_etag("abcd1234");
+        let header = format!("{}, {}", make_etag("ffff0000"), etag);
+
+        assert!(etag_matches(&header, &etag));
+    }
+
+    #[test]
+    fn etag_matches_rejects_different_hash() {
+        assert!(!etag_matches(
+            &make_etag("abcd1234"),
+            &make_etag("ffff0000")
+        ));
+    }
+
+    #[test]
+    fn cache_control_is_public_immutable_with_a_long_max_age() {
+        let header = cache_control();
+
+        assert!(header.starts_with("public, max-age="));
+        assert!(header.ends_with("immutable"));
+    }
+
+    #[test]
+    fn not_modified_since_accepts_an_equal_if_modified_since() {
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let if_modified_since = make_last_modified(created_at);
+
+        assert!(not_modified_since(&if_modified_since, created_at));
+    }
+
+    #[test]
+    fn not_modified_since_rejects_an_earlier_if_modified_since() {
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let earlier = make_last_modified(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap());
+
+        assert!(!not_modified_since(&earlier, created_at));
+    }
+
+    #[test]
+    fn not_modified_since_ignores_an_unparsable_header() {
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+
+        assert!(!not_modified_since("not-a-date", created_at));
+    }
+}
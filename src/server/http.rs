@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::channel;
+use uuid::Uuid;
 use warp::http::{self, StatusCode};
 use warp::Filter;
 
@@ -20,6 +21,23 @@ struct ChatQueryParams {
     pub token: String,
 }
 
+/// Query parameters expected by the file delete endpoint
+#[derive(Deserialize, Serialize)]
+struct DeleteFileQuery {
+    pub delete_token: Uuid,
+}
+
+/// Query parameters accepted by the image variant endpoint, e.g.
+/// `?w=320&h=320&fit=cover&format=webp`
+#[derive(Deserialize, Serialize)]
+struct VariantQuery {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<String>,
+    pub format: Option<String>,
+    pub quality: Option<u8>,
+}
+
 pub struct Http {
     pub port: u16,
 }
@@ -41,12 +59,15 @@ impl Http {
             .allow_headers(vec![
                 http::header::AUTHORIZATION,
                 http::header::CONTENT_TYPE,
+                http::header::IF_NONE_MATCH,
+                http::header::IF_MODIFIED_SINCE,
             ])
             .allow_methods(&[
                 http::Method::GET,
                 http::Method::OPTIONS,
                 http::Method::POST,
                 http::Method::PUT,
+                http::Method::DELETE,
             ]);
 
         let api = warp::path("api");
@@ -83,8 +104,33 @@ impl Http {
             .and(with_authorization())
             .and(with_service(services.clone()))
             .and(warp::path::param())
+            .and(warp::header::optional::<String>("if-none-match"))
+            .and(warp::header::optional::<String>("if-modified-since"))
             .and_then(handler::files::download);
 
+        let delete_file = files
+            .and(with_service(services.clone()))
+            .and(warp::path::param())
+            .and(warp::query::<DeleteFileQuery>())
+            .and_then(handler::files::delete);
+
+        let upload_status = files
+            .and(warp::path("status"))
+            .and(with_authorization())
+            .and(with_service(services.clone()))
+            .and(warp::path::param())
+            .and_then(handler::files::upload_status);
+
+        let images = api_v1.and(warp::path("images"));
+
+        let download_variant = images
+            .and(with_authorization())
+            .and(with_service(services.clone()))
+            .and(warp::path::param())
+            .and(warp::query::<VariantQuery>())
+            .and(warp::header::optional::<String>("if-none-match"))
+            .and_then(handler::images::download_variant);
+
         let profiles = api_v1.and(warp::path("profiles"));
 
         let upload_avatar = profiles
@@ -94,9 +140,11 @@ impl Http {
             .and(warp::multipart::form().max_length(MAX_FILE_SIZE))
             .and_then(handler::profiles::upload_avatar);
 
-        let get_routes = warp::get().and(login.or(me.or(download_file)));
+        let get_routes =
+            warp::get().and(login.or(me.or(upload_status.or(download_file.or(download_variant)))));
         let post_routes = warp::post().and(signup.or(upload_file).or(upload_avatar));
-        let routes = get_routes.or(post_routes);
+        let delete_routes = warp::delete().and(delete_file);
+        let routes = get_routes.or(post_routes).or(delete_routes);
         let routes = routes.recover(handler::rejection::handle_rejection);
         let serving_proccess = warp::serve(routes.with(cors)).bind(([127, 0, 0, 1], self.port));
     }
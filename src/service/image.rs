@@ -1,13 +1,15 @@
 use bytes::BufMut;
+use chrono::{DateTime, Utc};
+use exif::{In, Reader as ExifReader, Tag};
 use futures::TryStreamExt;
-use image::{load_from_memory, GenericImageView};
+use image::io::Reader as ImageReader;
+use image::{guess_format, load_from_memory, DynamicImage, ImageFormat};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use sqlx::FromRow;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::io::Cursor;
 use std::string::ToString;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use warp::filters::multipart::Part;
 
@@ -15,8 +17,28 @@ use crate::error::AppError;
 use crate::model::image::Image;
 use crate::{database::DbPool, error::Result};
 
+use super::store::Store;
 use super::url::UrlService;
 
+/// Rejects images whose dimensions would decode into more pixels than this,
+/// guarding against decompression-bomb uploads before the full decode runs.
+const MAX_IMAGE_PIXELS: u64 = 40_000_000;
+
+/// Row shape for `images` queries that only need metadata: the bytes
+/// themselves live in the configured `Store`, not in Postgres.
+#[derive(FromRow)]
+struct ImageMetaRow {
+    id: Uuid,
+    url: String,
+    filename: String,
+    size: i32,
+    mime: String,
+    hash: String,
+    height: i16,
+    width: i16,
+    created_at: DateTime<Utc>,
+}
+
 #[derive(Clone, FromRow, Serialize)]
 pub struct ImageResource {
     pub id: Uuid,
@@ -48,24 +70,82 @@ impl ImageResource {
 pub struct ImageService {
     db_conn: DbPool,
     url_service: Arc<UrlService>,
+    store: Arc<dyn Store>,
 }
 
 impl ImageService {
-    pub fn new(db_conn: DbPool, url_service: Arc<UrlService>) -> Self {
+    pub fn new(db_conn: DbPool, url_service: Arc<UrlService>, store: Arc<dyn Store>) -> Self {
         Self {
             db_conn,
             url_service,
+            store,
         }
     }
 
+    /// Decodes and sanitizes `p`: the declared `Content-Type` is checked
+    /// against the format sniffed from magic bytes (rejecting a mismatch),
+    /// the pixel dimensions are capped before the image is fully decoded to
+    /// defend against decompression bombs, and the result is always
+    /// re-encoded through the `image` crate so no EXIF/GPS metadata from the
+    /// original file survives. The canonical blob always keeps the source
+    /// format: format negotiation against a viewer's `Accept` header happens
+    /// at serving time (see `ProcessService::variant`), not here, so one
+    /// upload always produces one canonical, content-addressed blob no
+    /// matter which client uploaded it.
     pub async fn from_part<'a>(&self, p: Part) -> Result<Image> {
-        let mime = self.get_content_type(&p);
+        let declared_mime = self.get_content_type(&p);
         let bytes = self.part_bytes(p).await?;
-        let image = bytes.clone();
-        let size: i32 = bytes.len() as i32;
-        let img = load_from_memory(&bytes)?;
-        let (height, width) = img.dimensions();
-        let filename = self.make_filename(size, &mime)?;
+
+        self.from_bytes_blocking(bytes, declared_mime).await
+    }
+
+    /// Runs [`from_bytes`] on the blocking thread pool. Decode and re-encode
+    /// are CPU-heavy enough to run for the whole duration of a
+    /// large upload; a bounded `Semaphore` only caps how many run at once,
+    /// it doesn't stop one of them from starving the tokio worker thread
+    /// it runs on (and everything else scheduled on that thread, including
+    /// the chat websocket) for as long as it takes.
+    pub(crate) async fn from_bytes_blocking(
+        &self,
+        bytes: Vec<u8>,
+        declared_mime: String,
+    ) -> Result<Image> {
+        let this = self.clone();
+
+        tokio::task::spawn_blocking(move || this.from_bytes(bytes, declared_mime))
+            .await
+            .map_err(|e| AppError::ProcessingError(e.to_string()))?
+    }
+
+    /// The byte-oriented core of [`from_part`], split out so backgrounded
+    /// ingest (which only has raw bytes, not a multipart `Part`) can run the
+    /// same validate/strip/encode pipeline. Callers on the async side should
+    /// go through [`from_bytes_blocking`] rather than calling this directly.
+    pub(crate) fn from_bytes(&self, bytes: Vec<u8>, declared_mime: String) -> Result<Image> {
+        let sniffed_format = verify_declared_format(&bytes, &declared_mime)?;
+
+        let (width, height) = ImageReader::with_format(Cursor::new(&bytes), sniffed_format)
+            .into_dimensions()
+            .map_err(|e| AppError::UnsupportedImage(e.to_string()))?;
+
+        check_pixel_budget(width, height)?;
+
+        // Read the EXIF orientation before decoding loses access to it, and
+        // apply it to the decoded pixel buffer: the `image` crate's decoders
+        // don't auto-rotate per the tag, so skipping this leaves portrait
+        // phone photos sideways once `reencode` strips the EXIF data below.
+        let orientation = read_exif_orientation(&bytes);
+        let decoded = apply_exif_orientation(load_from_memory(&bytes)?, orientation);
+        let (image, mime) = self.reencode(&decoded, sniffed_format)?;
+        // Hash the bytes we actually store/serve, not the pre-transcode
+        // upload, so the hash stays a valid content address (and ETag) for
+        // it. Since the canonical blob is always the source format now,
+        // byte-identical uploads always hash the same regardless of who
+        // uploaded them or what they sent as `Accept`.
+        let hash = self.hash_bytes(&image);
+        let size: i32 = image.len() as i32;
+        let file_extension = self.extension_from_mime(&mime)?;
+        let filename = format!("{}.{}", hash, file_extension);
         let url = self
             .url_service
             .create_server_url(&format!("api/v1/images/{}", filename))?
@@ -74,18 +154,100 @@ impl ImageService {
         Ok(Image {
             id: uuid::Uuid::default(),
             url,
-            filename: String::from(filename),
+            filename,
             image,
             size,
             mime,
+            hash,
             height: height as i16,
             width: width as i16,
+            // Overwritten with the row's actual value once `save` persists
+            // it; this is only read before that happens if a caller inspects
+            // an `Image` it hasn't saved yet.
+            created_at: Utc::now(),
         })
     }
 
-    pub async fn save(&self, image: Image, owner_id: Uuid) -> Result<Image> {
-        sqlx::query_as(
-            r#"
+    /// Re-encodes `decoded` through the `image` crate, which drops any
+    /// metadata (EXIF/GPS) the original file carried since only the decoded
+    /// pixel buffer survives. Always targets `source_format`: the canonical
+    /// blob this produces is the one every `Accept`-negotiated encoding is
+    /// lazily transcoded from at serving time, so it must stay stable.
+    fn reencode(
+        &self,
+        decoded: &DynamicImage,
+        source_format: ImageFormat,
+    ) -> Result<(Vec<u8>, String)> {
+        let mut bytes = Vec::new();
+        decoded
+            .write_to(&mut Cursor::new(&mut bytes), source_format)
+            .map_err(|e| AppError::UnsupportedImage(e.to_string()))?;
+
+        Ok((bytes, mime_for_format(source_format).to_string()))
+    }
+
+    /// Persists `image`, deduplicating by content hash. If a blob with the
+    /// same hash already exists, a new alias row is inserted pointing at it
+    /// instead of storing the bytes again; otherwise the canonical row is
+    /// inserted first. Either way, every save — including the very first
+    /// upload of a given hash — gets its own `image_aliases` row, so
+    /// ref-counted deletion (see `delete`) has something to look up
+    /// regardless of which upload it was. Returns the stored (or shared)
+    /// `Image` together with the delete token minted for this particular
+    /// alias. The bytes themselves go to the configured `Store`; Postgres
+    /// only keeps metadata.
+    ///
+    /// The find-or-create and the alias insert run inside one transaction,
+    /// with `FOR UPDATE` locking the canonical row so a concurrent `delete`
+    /// can't garbage-collect it between our lookup and our alias insert.
+    ///
+    /// A plain `SELECT ... FOR UPDATE` takes no lock at all when zero rows
+    /// match, so two first-time uploads of identical bytes can both read
+    /// `None` and both try to `INSERT`, with the loser bubbling up the
+    /// `images_hash_idx` unique-violation as a raw DB error instead of
+    /// deduping. `pg_advisory_xact_lock` closes that window: it's taken
+    /// before the lookup and held for the rest of the transaction, so a
+    /// concurrent `save` for the same hash queues behind us instead of
+    /// racing the unique index.
+    pub async fn save(&self, image: Image, owner_id: Uuid) -> Result<(Image, Uuid)> {
+        let mut tx = self.db_conn.begin().await.map_err(AppError::from)?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1)::bigint)")
+            .bind(&image.hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::from)?;
+
+        let existing: Option<ImageMetaRow> = sqlx::query_as(
+            "SELECT id, url, filename, size, mime, hash, height, width, created_at FROM images WHERE hash = $1 FOR UPDATE",
+        )
+        .bind(&image.hash)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::from)?;
+
+        let canonical = match existing {
+            // No `store.load` here: callers of `save` only read the
+            // returned `Image`'s metadata (see `ImageResource::from_image`),
+            // so fetching the blob would just hold the `FOR UPDATE` lock
+            // open for a slow `Store` round-trip for no reason.
+            Some(row) => Image {
+                id: row.id,
+                url: row.url,
+                filename: row.filename,
+                image: Vec::new(),
+                size: row.size,
+                mime: row.mime,
+                hash: row.hash,
+                height: row.height,
+                width: row.width,
+                created_at: row.created_at,
+            },
+            None => {
+                self.store.save(&image.hash, &image.image).await?;
+
+                let row: ImageMetaRow = sqlx::query_as(
+                    r#"
         INSERT INTO images (
             height,
             width,
@@ -93,8 +255,7 @@ impl ImageService {
             filename,
             url,
             size,
-            image,
-            owner_id
+            hash
         ) VALUES (
             $1,
             $2,
@@ -102,47 +263,300 @@ impl ImageService {
             $4,
             $5,
             $6,
-            $7,
-            $8
-        ) RETURNING *"#,
+            $7
+        ) RETURNING id, url, filename, size, mime, hash, height, width, created_at"#,
+                )
+                .bind(&image.height)
+                .bind(&image.width)
+                .bind(&image.mime)
+                .bind(&image.filename)
+                .bind(&image.url)
+                .bind(&image.size)
+                .bind(&image.hash)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(AppError::from)?;
+
+                Image {
+                    id: row.id,
+                    url: row.url,
+                    filename: row.filename,
+                    image: image.image,
+                    size: row.size,
+                    mime: row.mime,
+                    hash: row.hash,
+                    height: row.height,
+                    width: row.width,
+                    created_at: row.created_at,
+                }
+            }
+        };
+
+        let delete_token = Uuid::new_v4();
+
+        sqlx::query(
+            r#"
+        INSERT INTO image_aliases (
+            image_id,
+            filename,
+            owner_id,
+            delete_token
+        ) VALUES ($1, $2, $3, $4)"#,
         )
-        .bind(&image.height)
-        .bind(&image.width)
-        .bind(&image.mime)
-        .bind(&image.filename)
-        .bind(&image.url)
-        .bind(&image.size)
-        .bind(&image.image.as_slice())
+        .bind(canonical.id)
+        .bind(&canonical.filename)
         .bind(owner_id)
-        .fetch_one(&self.db_conn)
+        .bind(delete_token)
+        .execute(&mut *tx)
         .await
-        .map_err(AppError::from)
+        .map_err(AppError::from)?;
+
+        tx.commit().await.map_err(AppError::from)?;
+
+        Ok((canonical, delete_token))
     }
 
-    pub async fn download(&self, url: String) -> Result<Image> {
-        sqlx::query_as("SELECT * FROM images WHERE filename = $1")
-            .bind(&url)
-            .fetch_one(&self.db_conn)
+    /// Removes the alias identified by `filename`, provided `delete_token`
+    /// matches the one handed back to its uploader. The shared blob in
+    /// `images` is only garbage-collected once its last alias is gone.
+    ///
+    /// The alias removal, ref-count, and canonical-row removal run inside
+    /// one transaction that takes the same `pg_advisory_xact_lock(hash)` and
+    /// `FOR UPDATE` lock `save` takes, so a concurrent `save` can't insert a
+    /// fresh alias against a row this call is about to delete.
+    pub async fn delete(&self, filename: &str, delete_token: Uuid) -> Result<()> {
+        let mut tx = self.db_conn.begin().await.map_err(AppError::from)?;
+
+        let alias: (Uuid, Uuid) = sqlx::query_as(
+            "SELECT id, image_id FROM image_aliases WHERE filename = $1 AND delete_token = $2",
+        )
+        .bind(filename)
+        .bind(delete_token)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| AppError::NotFound(format!("file {} not found", filename)))?;
+
+        let (alias_id, image_id) = alias;
+
+        let hash: String = sqlx::query_scalar("SELECT hash FROM images WHERE id = $1")
+            .bind(image_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(AppError::from)?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1)::bigint)")
+            .bind(&hash)
+            .execute(&mut *tx)
             .await
-            .map_err(AppError::from)
+            .map_err(AppError::from)?;
+
+        sqlx::query_scalar::<_, Uuid>("SELECT id FROM images WHERE id = $1 FOR UPDATE")
+            .bind(image_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(AppError::from)?;
+
+        sqlx::query("DELETE FROM image_aliases WHERE id = $1")
+            .bind(alias_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::from)?;
+
+        let remaining_aliases: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM image_aliases WHERE image_id = $1")
+                .bind(image_id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(AppError::from)?;
+
+        let blob_to_gc = if remaining_aliases == 0 {
+            sqlx::query("DELETE FROM images WHERE id = $1")
+                .bind(image_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(AppError::from)?;
+
+            Some(hash)
+        } else {
+            None
+        };
+
+        tx.commit().await.map_err(AppError::from)?;
+
+        if let Some(hash) = blob_to_gc {
+            self.gc_blob(&hash).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-checks, in a fresh transaction that takes the same
+    /// `pg_advisory_xact_lock(hash)` `save` takes, that no row for `hash`
+    /// has reappeared before deleting its blob from `Store`.
+    ///
+    /// Without this, a `save` blocked behind `delete`'s `FOR UPDATE` lock
+    /// can unblock right after `delete`'s commit above, find the row gone,
+    /// and insert a fresh one — re-writing the blob via `store.save` in the
+    /// process. If this GC then ran unconditionally it could delete that
+    /// freshly written blob out from under the row that now depends on it
+    /// (e.g. delete-then-immediately-reupload of the same popular image).
+    /// Taking the lock `save` also takes forces this check to happen either
+    /// fully before or fully after any such `save`, never interleaved with
+    /// one.
+    async fn gc_blob(&self, hash: &str) -> Result<()> {
+        let mut tx = self.db_conn.begin().await.map_err(AppError::from)?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1)::bigint)")
+            .bind(hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::from)?;
+
+        let still_exists: Option<Uuid> =
+            sqlx::query_scalar("SELECT id FROM images WHERE hash = $1 FOR UPDATE")
+                .bind(hash)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(AppError::from)?;
+
+        if still_exists.is_none() {
+            self.store.delete(hash).await?;
+        }
+
+        tx.commit().await.map_err(AppError::from)?;
+
+        Ok(())
+    }
+
+    /// Looks up a previously generated variant by its cache key, returning
+    /// its bytes and mime type when present. The metadata row only confirms
+    /// the variant exists and what mime it was encoded as; the bytes
+    /// themselves come from the configured `Store`, keyed by `variant_key`,
+    /// same as canonical images.
+    pub async fn find_variant(&self, variant_key: &str) -> Result<Option<(Vec<u8>, String)>> {
+        let mime: Option<String> =
+            sqlx::query_scalar("SELECT mime FROM image_variants WHERE variant_key = $1")
+                .bind(variant_key)
+                .fetch_optional(&self.db_conn)
+                .await
+                .map_err(AppError::from)?;
+
+        let mime = match mime {
+            Some(mime) => mime,
+            None => return Ok(None),
+        };
+
+        let bytes = self.store.load(variant_key).await?;
+
+        Ok(Some((bytes, mime)))
+    }
+
+    /// Caches a generated variant so future requests with the same
+    /// `(source_hash, normalized_params)` skip reprocessing. Generated
+    /// variants can be as large as the source image, so the bytes go to the
+    /// configured `Store` just like `save`'s canonical blobs; only
+    /// `variant_key`/`mime` are kept in Postgres.
+    pub async fn save_variant(&self, variant_key: &str, bytes: &[u8], mime: &str) -> Result<()> {
+        self.store.save(variant_key, bytes).await?;
+
+        sqlx::query(
+            r#"
+        INSERT INTO image_variants (
+            variant_key,
+            mime
+        ) VALUES ($1, $2)
+        ON CONFLICT (variant_key) DO NOTHING"#,
+        )
+        .bind(variant_key)
+        .bind(mime)
+        .execute(&self.db_conn)
+        .await
+        .map_err(AppError::from)?;
+
+        Ok(())
+    }
+
+    /// One-shot migration helper: copies every row's `image` bytea into the
+    /// configured `Store`, keyed by `hash`. Must be run to completion against
+    /// the running deployment before the `images_drop_bytea` migration is
+    /// applied, since that migration drops the only other place these bytes
+    /// live. Safe to re-run — re-saving an already-migrated key is a
+    /// harmless overwrite.
+    pub async fn backfill_store(&self) -> Result<u64> {
+        let rows: Vec<(String, Vec<u8>)> =
+            sqlx::query_as("SELECT hash, image FROM images WHERE image IS NOT NULL")
+                .fetch_all(&self.db_conn)
+                .await
+                .map_err(AppError::from)?;
+
+        let migrated = rows.len() as u64;
+
+        for (hash, bytes) in rows {
+            self.store.save(&hash, &bytes).await?;
+        }
+
+        Ok(migrated)
+    }
+
+    async fn hydrate(&self, row: ImageMetaRow) -> Result<Image> {
+        let image = self.store.load(&row.hash).await?;
+
+        Ok(Image {
+            id: row.id,
+            url: row.url,
+            filename: row.filename,
+            image,
+            size: row.size,
+            mime: row.mime,
+            hash: row.hash,
+            height: row.height,
+            width: row.width,
+            created_at: row.created_at,
+        })
     }
 
-    pub async fn get_info(&self, id: Uuid) -> Result<ImageResource> {
+    fn hash_bytes(&self, bytes: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(bytes);
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub async fn download(&self, url: String) -> Result<Image> {
+        let row: ImageMetaRow = sqlx::query_as(
+            "SELECT id, url, filename, size, mime, hash, height, width, created_at FROM images WHERE filename = $1",
+        )
+        .bind(&url)
+        .fetch_one(&self.db_conn)
+        .await
+        .map_err(AppError::from)?;
+
+        self.hydrate(row).await
+    }
+
+    /// Looks up a single alias by its own id. Ownership lives on
+    /// `image_aliases`, not `images`, since one canonical row can be shared
+    /// by many uploaders' aliases.
+    pub async fn get_info(&self, alias_id: Uuid) -> Result<ImageResource> {
         sqlx::query_as(
             r#"
         SELECT
-            height,
-            width,
-            mime,
-            filename,
-            size,
-            owner_id
+            images.id,
+            images.height,
+            images.width,
+            images.mime,
+            images.url,
+            images.size,
+            image_aliases.filename,
+            image_aliases.owner_id
         FROM
-            images
+            image_aliases
+        INNER JOIN images ON images.id = image_aliases.image_id
         WHERE
-            id = $1"#,
+            image_aliases.id = $1"#,
         )
-        .bind(&id)
+        .bind(&alias_id)
         .fetch_one(&self.db_conn)
         .await
         .map_err(AppError::from)
@@ -171,35 +585,226 @@ impl ImageService {
         }
     }
 
-    fn make_filename(&self, size: i32, mime: &str) -> Result<String> {
-        let mut state = DefaultHasher::new();
-        let file_extension = self.extension_from_mime(mime)?;
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();
-
-        let temporal_name = format!(
-            "{}_{}_{}_{}",
-            Uuid::new_v4().to_string(),
-            size,
-            file_extension,
-            timestamp
-        );
-
-        temporal_name.hash(&mut state);
-
-        Ok(format!("{}.{}", state.finish(), file_extension))
-    }
-
     fn extension_from_mime(&self, mime: &str) -> Result<String> {
         match mime {
             "image/jpeg" => Ok(String::from("jpeg")),
             "image/png" => Ok(String::from("png")),
+            "image/webp" => Ok(String::from("webp")),
+            "image/avif" => Ok(String::from("avif")),
             _ => Err(AppError::UnsupportedImage(format!(
                 "MIME type {} is not supported",
                 mime
             ))),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Picks the most efficient output format the client advertised via
+/// `Accept`, preferring AVIF over WebP when both are present.
+pub(crate) fn negotiate_format(accept: Option<&str>) -> Option<ImageFormat> {
+    let accept = accept?;
+
+    if accept.contains("image/avif") {
+        Some(ImageFormat::Avif)
+    } else if accept.contains("image/webp") {
+        Some(ImageFormat::WebP)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn mime_for_format(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Avif => "image/avif",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Png => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Inverse of [`mime_for_format`], used to compare the client-declared
+/// `Content-Type` against the format sniffed from the upload's magic bytes.
+fn mime_to_format(mime: &str) -> Option<ImageFormat> {
+    match mime {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/webp" => Some(ImageFormat::WebP),
+        "image/avif" => Some(ImageFormat::Avif),
+        _ => None,
+    }
+}
+
+/// Resolves `declared_mime` to an `ImageFormat` and checks it against the
+/// format sniffed from `bytes`' magic numbers, rejecting anything that
+/// doesn't match (or that declares an unsupported MIME type). Split out of
+/// `from_bytes` so the sniff-vs-declared check can be unit tested without a
+/// full `ImageService`.
+fn verify_declared_format(bytes: &[u8], declared_mime: &str) -> Result<ImageFormat> {
+    let declared_format = mime_to_format(declared_mime).ok_or_else(|| {
+        AppError::FormatMismatch(format!("unsupported declared MIME type {}", declared_mime))
+    })?;
+    let sniffed_format =
+        guess_format(bytes).map_err(|e| AppError::FormatMismatch(e.to_string()))?;
+
+    if sniffed_format != declared_format {
+        return Err(AppError::FormatMismatch(format!(
+            "declared Content-Type {} does not match the uploaded file's actual format",
+            declared_mime
+        )));
+    }
+
+    Ok(sniffed_format)
+}
+
+/// Rejects dimensions that would decode into more than `MAX_IMAGE_PIXELS`
+/// pixels, guarding against decompression-bomb uploads before the full
+/// decode runs. Split out of `from_bytes` for the same reason as
+/// [`verify_declared_format`].
+fn check_pixel_budget(width: u32, height: u32) -> Result<()> {
+    if (width as u64) * (height as u64) > MAX_IMAGE_PIXELS {
+        return Err(AppError::ImageTooLarge(format!(
+            "{}x{} exceeds the {} pixel limit",
+            width, height, MAX_IMAGE_PIXELS
+        )));
+    }
+
+    Ok(())
+}
+
+/// Reads the raw EXIF `Orientation` tag (1-8) out of `bytes`, defaulting to
+/// 1 (no-op) when the file carries no EXIF data or the tag is absent, which
+/// covers most non-JPEG uploads and already-upright photos.
+fn read_exif_orientation(bytes: &[u8]) -> u32 {
+    ExifReader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0))
+        .unwrap_or(1)
+}
+
+/// Rotates/flips `img` to account for `orientation`, the raw EXIF tag value
+/// read by [`read_exif_orientation`]. Must run before the image is
+/// re-encoded, since re-encoding through the `image` crate is what discards
+/// the EXIF tag itself.
+fn apply_exif_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_format_prefers_avif_over_webp() {
+        assert_eq!(
+            negotiate_format(Some("text/html,image/webp,image/avif")),
+            Some(ImageFormat::Avif)
+        );
+    }
+
+    #[test]
+    fn negotiate_format_falls_back_to_webp() {
+        assert_eq!(
+            negotiate_format(Some("text/html,image/webp")),
+            Some(ImageFormat::WebP)
+        );
+    }
+
+    #[test]
+    fn negotiate_format_returns_none_when_unsupported_or_absent() {
+        assert_eq!(negotiate_format(Some("text/html")), None);
+        assert_eq!(negotiate_format(None), None);
+    }
+
+    #[test]
+    fn mime_to_format_round_trips_through_mime_for_format() {
+        for format in [
+            ImageFormat::Jpeg,
+            ImageFormat::Png,
+            ImageFormat::WebP,
+            ImageFormat::Avif,
+        ] {
+            assert_eq!(mime_to_format(mime_for_format(format)), Some(format));
+        }
+    }
+
+    #[test]
+    fn mime_to_format_rejects_unsupported_mime() {
+        assert_eq!(mime_to_format("image/gif"), None);
+    }
+
+    fn tiny_png_bytes() -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(2, 2));
+        let mut bytes = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn verify_declared_format_accepts_matching_mime() {
+        let bytes = tiny_png_bytes();
+
+        assert_eq!(
+            verify_declared_format(&bytes, "image/png").unwrap(),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn verify_declared_format_rejects_mismatched_mime() {
+        let bytes = tiny_png_bytes();
+
+        assert!(verify_declared_format(&bytes, "image/jpeg").is_err());
+    }
+
+    #[test]
+    fn verify_declared_format_rejects_unsupported_mime() {
+        let bytes = tiny_png_bytes();
+
+        assert!(verify_declared_format(&bytes, "image/gif").is_err());
+    }
+
+    #[test]
+    fn check_pixel_budget_rejects_images_over_the_cap() {
+        assert!(check_pixel_budget(10_000, 10_000).is_err());
+    }
+
+    #[test]
+    fn check_pixel_budget_accepts_images_within_the_cap() {
+        assert!(check_pixel_budget(100, 100).is_ok());
+    }
+
+    #[test]
+    fn read_exif_orientation_defaults_to_upright_without_exif_data() {
+        assert_eq!(read_exif_orientation(&tiny_png_bytes()), 1);
+    }
+
+    #[test]
+    fn apply_exif_orientation_is_a_no_op_for_upright() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(2, 3));
+
+        assert_eq!(
+            apply_exif_orientation(img.clone(), 1).dimensions(),
+            img.dimensions()
+        );
+    }
+
+    #[test]
+    fn apply_exif_orientation_swaps_dimensions_for_90_degree_rotations() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(2, 3));
+
+        assert_eq!(apply_exif_orientation(img, 6).dimensions(), (3, 2));
+    }
+}
@@ -0,0 +1,231 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{load_from_memory, DynamicImage, ImageFormat};
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::error::{AppError, Result};
+
+use super::image::{negotiate_format, ImageService};
+
+/// Caps how many variant transforms may run at once so a burst of
+/// differently-sized requests can't exhaust the CPU.
+const MAX_CONCURRENT_TRANSFORMS: usize = 4;
+
+/// Upper bound on a requested variant's width/height. The 4-slot semaphore
+/// only caps how many transforms run at once, not the cost of a single one,
+/// so an unbounded `?w=`/`?h=` still lets one request queue an arbitrarily
+/// expensive resize.
+const MAX_VARIANT_DIMENSION: u32 = 4_096;
+
+/// How width/height are reconciled when both are given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fit {
+    /// Scale to completely cover the target box, cropping the overflow.
+    Cover,
+    /// Scale to fit entirely within the target box, preserving aspect ratio.
+    Contain,
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit::Contain
+    }
+}
+
+/// Parsed `?w=&h=&fit=&format=&quality=` query parameters accepted by the
+/// image variant endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct VariantParams {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: Option<ImageFormat>,
+    pub quality: Option<u8>,
+}
+
+impl VariantParams {
+    /// Normalizes the params into a stable string so equivalent requests
+    /// always resolve to the same cached variant.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "w={}_h={}_fit={:?}_fmt={:?}_q={}",
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.fit,
+            self.format,
+            self.quality.unwrap_or(0)
+        )
+    }
+}
+
+/// Generates and caches derived variants (resize/crop/format) of images
+/// already stored by `ImageService`.
+#[derive(Clone)]
+pub struct ProcessService {
+    image_service: Arc<ImageService>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl ProcessService {
+    pub fn new(image_service: Arc<ImageService>) -> Self {
+        Self {
+            image_service,
+            semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_TRANSFORMS)),
+        }
+    }
+
+    /// Returns the bytes and mime type for `filename` processed according to
+    /// `params`, serving a cached variant when one already exists for the
+    /// `(source_hash, normalized_params)` pair. When `params.format` is
+    /// unset, `accept` is used to opportunistically upgrade the output to a
+    /// smaller format (e.g. WebP/AVIF) the client advertised support for.
+    pub async fn variant(
+        &self,
+        filename: &str,
+        params: VariantParams,
+        accept: Option<&str>,
+    ) -> Result<(Vec<u8>, String)> {
+        let source = self.image_service.download(filename.to_string()).await?;
+        let format_override = params.format.or_else(|| negotiate_format(accept));
+        // Clamp before the cache key is built, not after: otherwise every
+        // distinct out-of-range `w`/`h` mints its own row even though they
+        // all clamp down to the same output.
+        let width = params.width.map(|w| w.min(MAX_VARIANT_DIMENSION));
+        let height = params.height.map(|h| h.min(MAX_VARIANT_DIMENSION));
+        let variant_key = format!(
+            "{}__{}",
+            source.hash,
+            VariantParams {
+                width,
+                height,
+                format: format_override,
+                ..params.clone()
+            }
+            .cache_key()
+        );
+
+        if let Some(cached) = self.image_service.find_variant(&variant_key).await? {
+            return Ok(cached);
+        }
+
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .map_err(|e| AppError::ProcessingError(e.to_string()))?;
+
+        let format = format_override
+            .or_else(|| ImageFormat::from_mime_type(&source.mime))
+            .unwrap_or(ImageFormat::Jpeg);
+        let fit = params.fit;
+        let quality = params.quality;
+        let source_mime = source.mime;
+        let source_bytes = source.image;
+
+        // The semaphore above only bounds how many transforms run at once,
+        // not how long a single one blocks the tokio worker thread it runs
+        // on — decode/resize/encode are CPU-heavy enough to starve every
+        // other task on that thread (including the chat websocket) for the
+        // whole duration of a large transform, so the actual work runs on
+        // the blocking pool instead of inline.
+        let (bytes, mime) = tokio::task::spawn_blocking(move || -> Result<(Vec<u8>, String)> {
+            let decoded = load_from_memory(&source_bytes)?;
+            let resized = match (width, height) {
+                (Some(w), Some(h)) if fit == Fit::Cover => {
+                    decoded.resize_to_fill(w, h, FilterType::Lanczos3)
+                }
+                (Some(w), Some(h)) => decoded.resize(w, h, FilterType::Lanczos3),
+                (Some(w), None) => decoded.resize(w, u32::MAX, FilterType::Lanczos3),
+                (None, Some(h)) => decoded.resize(u32::MAX, h, FilterType::Lanczos3),
+                (None, None) => decoded,
+            };
+
+            let bytes = encode(&resized, format, quality)?;
+            let mime = format
+                .to_mime_type()
+                .map(String::from)
+                .unwrap_or(source_mime);
+
+            Ok((bytes, mime))
+        })
+        .await
+        .map_err(|e| AppError::ProcessingError(e.to_string()))??;
+
+        self.image_service
+            .save_variant(&variant_key, &bytes, &mime)
+            .await?;
+
+        Ok((bytes, mime))
+    }
+}
+
+/// Encodes `image` as `format`, honoring `quality` where the encoder
+/// supports it (currently JPEG) and falling back to the format's default
+/// otherwise.
+fn encode(image: &DynamicImage, format: ImageFormat, quality: Option<u8>) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    match (format, quality) {
+        (ImageFormat::Jpeg, Some(quality)) => {
+            JpegEncoder::new_with_quality(&mut bytes, quality)
+                .encode_image(image)
+                .map_err(|e| AppError::ProcessingError(e.to_string()))?;
+        }
+        _ => {
+            image
+                .write_to(&mut Cursor::new(&mut bytes), format)
+                .map_err(|e| AppError::ProcessingError(e.to_string()))?;
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_defaults_to_contain() {
+        assert_eq!(Fit::default(), Fit::Contain);
+    }
+
+    #[test]
+    fn cache_key_differs_by_fit_so_cover_and_contain_dont_collide() {
+        let cover = VariantParams {
+            width: Some(320),
+            height: Some(320),
+            fit: Fit::Cover,
+            ..Default::default()
+        };
+        let contain = VariantParams {
+            fit: Fit::Contain,
+            ..cover.clone()
+        };
+
+        assert_ne!(cover.cache_key(), contain.cache_key());
+    }
+
+    #[test]
+    fn cache_key_is_stable_for_equivalent_params() {
+        let a = VariantParams {
+            width: Some(100),
+            height: Some(200),
+            fit: Fit::Cover,
+            format: Some(ImageFormat::WebP),
+            quality: Some(80),
+        };
+        let b = a.clone();
+
+        assert_eq!(a.cache_key(), b.cache_key());
+    }
+
+    #[test]
+    fn cache_key_treats_unset_dimensions_as_zero() {
+        let params = VariantParams::default();
+
+        assert!(params.cache_key().contains("w=0_h=0"));
+    }
+}
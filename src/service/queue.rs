@@ -0,0 +1,151 @@
+use serde::Serialize;
+use sqlx::FromRow;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use uuid::Uuid;
+
+use crate::database::DbPool;
+use crate::error::{AppError, Result};
+
+use super::image::ImageService;
+
+/// How many ingest jobs may be decoded/hashed/persisted at once, bounding
+/// CPU usage under bursty or large uploads.
+const MAX_CONCURRENT_INGESTS: usize = 4;
+const QUEUE_CAPACITY: usize = 256;
+
+/// Raw bytes awaiting ingest, plus enough context to finish the pipeline
+/// off the request path.
+struct IngestJob {
+    upload_id: Uuid,
+    owner_id: Uuid,
+    declared_mime: String,
+    bytes: Vec<u8>,
+}
+
+/// Progress of a backgrounded upload, polled via `GET
+/// /api/v1/files/status/{upload_id}`.
+#[derive(Clone, FromRow, Serialize)]
+pub struct IngestStatus {
+    pub status: String,
+    pub image_id: Option<Uuid>,
+    pub delete_token: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+/// Accepts raw upload bytes, hands them to a bounded pool of background
+/// workers that run `ImageService`'s decode/validate/hash/persist pipeline,
+/// and lets callers poll the resulting job by id.
+#[derive(Clone)]
+pub struct IngestQueue {
+    db_conn: DbPool,
+    tx: mpsc::Sender<IngestJob>,
+}
+
+impl IngestQueue {
+    pub fn new(db_conn: DbPool, image_service: Arc<ImageService>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let rx = Arc::new(Mutex::new(rx));
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_INGESTS));
+
+        for _ in 0..MAX_CONCURRENT_INGESTS {
+            let rx = rx.clone();
+            let semaphore = semaphore.clone();
+            let image_service = image_service.clone();
+            let db_conn = db_conn.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let job = rx.lock().await.recv().await;
+
+                    let Some(job) = job else {
+                        break;
+                    };
+
+                    let _permit = semaphore.acquire().await;
+                    Self::run(&db_conn, &image_service, job).await;
+                }
+            });
+        }
+
+        Self { db_conn, tx }
+    }
+
+    /// Writes a pending job row and enqueues `bytes` for ingest, returning
+    /// immediately with the id the client polls for completion.
+    pub async fn enqueue(
+        &self,
+        owner_id: Uuid,
+        declared_mime: String,
+        bytes: Vec<u8>,
+    ) -> Result<Uuid> {
+        let upload_id = Uuid::new_v4();
+
+        sqlx::query("INSERT INTO ingest_jobs (id, owner_id, status) VALUES ($1, $2, 'pending')")
+            .bind(upload_id)
+            .bind(owner_id)
+            .execute(&self.db_conn)
+            .await
+            .map_err(AppError::from)?;
+
+        self.tx
+            .send(IngestJob {
+                upload_id,
+                owner_id,
+                declared_mime,
+                bytes,
+            })
+            .await
+            .map_err(|e| AppError::ProcessingError(e.to_string()))?;
+
+        Ok(upload_id)
+    }
+
+    /// Reports the current state of a previously enqueued upload, scoped to
+    /// the user who enqueued it so one uploader can't poll another's job
+    /// (and, since `IngestStatus` carries the `delete_token`, can't read
+    /// another uploader's delete token).
+    pub async fn status(&self, upload_id: Uuid, owner_id: Uuid) -> Result<IngestStatus> {
+        sqlx::query_as(
+            "SELECT status, image_id, delete_token, error FROM ingest_jobs WHERE id = $1 AND owner_id = $2",
+        )
+        .bind(upload_id)
+        .bind(owner_id)
+        .fetch_one(&self.db_conn)
+        .await
+        .map_err(AppError::from)
+    }
+
+    async fn run(db_conn: &DbPool, image_service: &ImageService, job: IngestJob) {
+        let decoded = image_service
+            .from_bytes_blocking(job.bytes, job.declared_mime)
+            .await;
+
+        let outcome = match decoded {
+            Ok(image) => image_service.save(image, job.owner_id).await,
+            Err(e) => Err(e),
+        };
+
+        match outcome {
+            Ok((image, delete_token)) => {
+                let _ = sqlx::query(
+                    "UPDATE ingest_jobs SET status = 'done', image_id = $2, delete_token = $3 WHERE id = $1",
+                )
+                .bind(job.upload_id)
+                .bind(image.id)
+                .bind(delete_token)
+                .execute(db_conn)
+                .await;
+            }
+            Err(e) => {
+                let _ = sqlx::query(
+                    "UPDATE ingest_jobs SET status = 'failed', error = $2 WHERE id = $1",
+                )
+                .bind(job.upload_id)
+                .bind(e.to_string())
+                .execute(db_conn)
+                .await;
+            }
+        }
+    }
+}
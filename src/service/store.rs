@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+use crate::error::{AppError, Result};
+
+/// Backs the byte storage for images, independent of the metadata kept in
+/// Postgres. Canonical blobs are keyed by their content hash and generated
+/// variants by a key that starts with their source's hash, so
+/// `ImageService` never needs to know which backend is configured.
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn save(&self, key: &str, bytes: &[u8]) -> Result<()>;
+    async fn load(&self, key: &str) -> Result<Vec<u8>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// Stores blobs on the local filesystem, sharded by the first two hex
+/// characters of the key so a single directory doesn't grow unbounded.
+pub struct FileStore {
+    root: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        let shard = &key[..2.min(key.len())];
+        self.root.join(shard).join(key)
+    }
+}
+
+#[async_trait]
+impl Store for FileStore {
+    async fn save(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path_for(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| AppError::StorageError(e.to_string()))?;
+        }
+
+        tokio::fs::write(path, bytes)
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+
+    async fn load(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path_for(key))
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        tokio::fs::remove_file(self.path_for(key))
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+}
+
+/// Stores blobs in an S3-compatible bucket.
+pub struct ObjectStore {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl ObjectStore {
+    pub fn new(client: aws_sdk_s3::Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl Store for ObjectStore {
+    async fn save(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(bytes.to_vec().into())
+            .send()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?;
+
+        let bytes = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?
+            .into_bytes()
+            .to_vec();
+
+        Ok(bytes)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+}